@@ -0,0 +1,60 @@
+use num_enum::IntoPrimitive;
+use solana_program::program_error::ProgramError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DexError {
+    #[error(transparent)]
+    ProgramError(#[from] ProgramError),
+    #[error("{0:?}")]
+    ErrorCode(#[from] DexErrorCode),
+}
+
+#[derive(Error, Debug, Eq, PartialEq, Clone, Copy, IntoPrimitive)]
+#[repr(u32)]
+pub enum DexErrorCode {
+    #[error("invalid market flags")]
+    InvalidMarketFlags = 1000,
+    #[error("invalid ask vault")]
+    InvalidAskVault,
+    #[error("invalid bid vault")]
+    InvalidBidVault,
+    #[error("invalid vault signer")]
+    InvalidVaultSignerNonce,
+    #[error("open orders account does not belong to this market")]
+    WrongOpenOrdersAccount,
+    #[error("order would self-trade")]
+    WouldSelfTrade,
+    #[error("insufficient funds to place or fill order")]
+    InsufficientFunds,
+    #[error("order book is full")]
+    OrderBookFull,
+    #[error("the resulting fill is below the requested slippage floor")]
+    SlippageOutsideTolerance,
+    #[error("an order was left resting on the book where none was allowed")]
+    TakeOnlyOrderLeftResting,
+    #[error("the token mint of the supplied account does not match the market")]
+    WrongCoinMint,
+    #[error("the token mint of the supplied account does not match the market")]
+    WrongPcMint,
+    #[error("the supplied token program does not own the given mint or vault")]
+    WrongTokenProgram,
+    #[error("an arithmetic operation overflowed")]
+    Overflow,
+}
+
+impl From<DexErrorCode> for ProgramError {
+    fn from(error_code: DexErrorCode) -> Self {
+        ProgramError::Custom(error_code.into())
+    }
+}
+
+pub type DexResult<T = ()> = Result<T, DexError>;
+
+pub(crate) fn check_assert(cond: bool, code: DexErrorCode) -> DexResult<()> {
+    if cond {
+        Ok(())
+    } else {
+        Err(DexError::ErrorCode(code))
+    }
+}
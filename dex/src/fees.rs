@@ -0,0 +1,29 @@
+//! Maker/taker fee schedule shared by the matching engine and the
+//! instruction handlers that settle proceeds back to users.
+
+/// Taker fee, expressed in basis points of the quote currency notional.
+pub const TAKER_FEE_BPS: u64 = 22;
+
+/// Maker rebate, expressed in basis points of the quote currency notional.
+/// Paid out of the taker fee once events are consumed.
+pub const MAKER_REBATE_BPS: u64 = 3;
+
+const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Rounds a native pc amount up to the nearest unit of fee owed, mirroring
+/// the "round in the house's favor" behavior used throughout the matching
+/// engine for all fee and rebate calculations.
+pub fn taker_fee(native_pc_qty: u64) -> u64 {
+    ceil_div(native_pc_qty, TAKER_FEE_BPS, BPS_DENOMINATOR)
+}
+
+pub fn maker_rebate(native_pc_qty: u64) -> u64 {
+    native_pc_qty
+        .saturating_mul(MAKER_REBATE_BPS)
+        .saturating_div(BPS_DENOMINATOR)
+}
+
+fn ceil_div(numerator: u64, multiplier: u64, denominator: u64) -> u64 {
+    let product = (numerator as u128) * (multiplier as u128);
+    ((product + denominator as u128 - 1) / denominator as u128) as u64
+}
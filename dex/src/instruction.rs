@@ -0,0 +1,547 @@
+//! Instruction definitions and client-side constructors for building
+//! `Instruction`s that target the dex program.
+
+use std::num::NonZeroU64;
+
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use solana_program::sysvar;
+
+use crate::matching::{OrderType, SelfTradeBehavior, Side};
+
+#[derive(Debug, Clone, Copy)]
+pub struct NewOrderInstructionV3 {
+    pub side: Side,
+    pub limit_price: NonZeroU64,
+    pub max_coin_qty: NonZeroU64,
+    pub max_native_pc_qty_including_fees: NonZeroU64,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub order_type: OrderType,
+    pub client_order_id: u64,
+    pub limit: u16,
+}
+
+/// Instruction payload for [`MarketInstruction::SendTake`]. Mirrors
+/// [`NewOrderInstructionV3`] with the addition of the slippage floors that
+/// gate the direct wallet payout.
+#[derive(Debug, Clone, Copy)]
+pub struct SendTakeInstruction {
+    pub side: Side,
+    pub limit_price: NonZeroU64,
+    pub max_coin_qty: NonZeroU64,
+    pub max_native_pc_qty_including_fees: NonZeroU64,
+    pub min_coin_qty: u64,
+    pub min_native_pc_qty: u64,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub order_type: OrderType,
+    pub client_order_id: u64,
+    pub limit: u16,
+}
+
+/// Instruction payload for [`MarketInstruction::Swap`]. `side` picks the
+/// direction (`Bid` spends pc for coin, `Ask` spends coin for pc);
+/// `native_qty_in` is denominated in whichever token is being spent.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapInstruction {
+    pub side: Side,
+    pub native_qty_in: u64,
+    pub min_expected_native_qty_out: u64,
+    pub order_type: OrderType,
+    pub limit: u16,
+}
+
+/// Instruction payload for [`MarketInstruction::CancelOrder`]. `order_id`
+/// is the same 128-bit id returned in the `Order` that was placed, so the
+/// owner doesn't need to track a price/slot pair to cancel it.
+#[derive(Debug, Clone, Copy)]
+pub struct CancelOrderInstruction {
+    pub side: Side,
+    pub order_id: u128,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InitializeMarketInstruction {
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub fee_rate_bps: u16,
+    pub vault_signer_nonce: u64,
+    pub pc_dust_threshold: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum MarketInstruction {
+    InitializeMarket(InitializeMarketInstruction),
+    NewOrderV3(NewOrderInstructionV3),
+    SendTake(SendTakeInstruction),
+    Swap(SwapInstruction),
+    InitOpenOrders,
+    MatchOrders(u16),
+    ConsumeEvents(u16),
+    SettleFunds,
+    CancelOrder(CancelOrderInstruction),
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_market(
+    market: &Pubkey,
+    program_id: &Pubkey,
+    coin_mint: &Pubkey,
+    pc_mint: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    authority: Option<&Pubkey>,
+    prune_authority: Option<&Pubkey>,
+    fee_rate_authority: Option<&Pubkey>,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    req_q: &Pubkey,
+    event_q: &Pubkey,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    vault_signer_nonce: u64,
+    pc_dust_threshold: u64,
+    fee_rate_bps: u16,
+) -> Result<Instruction, ProgramError> {
+    let _ = (authority, prune_authority, fee_rate_authority);
+    let data = MarketInstruction::InitializeMarket(InitializeMarketInstruction {
+        coin_lot_size,
+        pc_lot_size,
+        fee_rate_bps,
+        vault_signer_nonce,
+        pc_dust_threshold,
+    });
+    let accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*req_q, false),
+        AccountMeta::new(*event_q, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new_readonly(*coin_vault, false),
+        AccountMeta::new_readonly(*pc_vault, false),
+        AccountMeta::new_readonly(*coin_mint, false),
+        AccountMeta::new_readonly(*pc_mint, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    Ok(encode(program_id, accounts, &data))
+}
+
+pub fn init_open_orders(
+    program_id: &Pubkey,
+    open_orders: &Pubkey,
+    owner: &Pubkey,
+    market: &Pubkey,
+    market_authority: Option<&Pubkey>,
+) -> Result<Instruction, ProgramError> {
+    let mut accounts = vec![
+        AccountMeta::new(*open_orders, false),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    if let Some(market_authority) = market_authority {
+        accounts.push(AccountMeta::new_readonly(*market_authority, true));
+    }
+    Ok(encode(program_id, accounts, &MarketInstruction::InitOpenOrders))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn new_order(
+    market: &Pubkey,
+    open_orders: &Pubkey,
+    req_q: &Pubkey,
+    event_q: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    order_payer: &Pubkey,
+    open_orders_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    spl_token_program_id: Pubkey,
+    rent_sysvar_id: Pubkey,
+    srm_account_referral: Option<&Pubkey>,
+    program_id: &Pubkey,
+    side: Side,
+    limit_price: NonZeroU64,
+    max_coin_qty: NonZeroU64,
+    order_type: OrderType,
+) -> Result<Instruction, ProgramError> {
+    let data = MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+        side,
+        limit_price,
+        max_coin_qty,
+        max_native_pc_qty_including_fees: max_coin_qty,
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type,
+        client_order_id: 0,
+        limit: 65535,
+    });
+    let mut accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*open_orders, false),
+        AccountMeta::new(*req_q, false),
+        AccountMeta::new(*event_q, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new(*order_payer, false),
+        AccountMeta::new_readonly(*open_orders_owner, true),
+        AccountMeta::new(*coin_vault, false),
+        AccountMeta::new(*pc_vault, false),
+        AccountMeta::new_readonly(spl_token_program_id, false),
+        AccountMeta::new_readonly(rent_sysvar_id, false),
+    ];
+    if let Some(referral) = srm_account_referral {
+        accounts.push(AccountMeta::new(*referral, false));
+    }
+    Ok(encode(program_id, accounts, &data))
+}
+
+/// Constructs a `SendTake` instruction: an IOC taker order whose proceeds
+/// are transferred directly to `coin_wallet`/`pc_wallet` rather than
+/// accrued on `open_orders`.
+#[allow(clippy::too_many_arguments)]
+pub fn send_take(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    req_q: &Pubkey,
+    event_q: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    coin_wallet: &Pubkey,
+    pc_wallet: &Pubkey,
+    vault_signer: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    fee_owner: &Pubkey,
+    side: Side,
+    limit_price: NonZeroU64,
+    max_coin_qty: NonZeroU64,
+    max_native_pc_qty_including_fees: NonZeroU64,
+    min_coin_qty: u64,
+    min_native_pc_qty: u64,
+    limit: u16,
+) -> Result<Instruction, ProgramError> {
+    let data = MarketInstruction::SendTake(SendTakeInstruction {
+        side,
+        limit_price,
+        max_coin_qty,
+        max_native_pc_qty_including_fees,
+        min_coin_qty,
+        min_native_pc_qty,
+        self_trade_behavior: SelfTradeBehavior::DecrementTake,
+        order_type: OrderType::ImmediateOrCancel,
+        client_order_id: 0,
+        limit,
+    });
+    let accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*req_q, false),
+        AccountMeta::new(*event_q, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new(*coin_vault, false),
+        AccountMeta::new(*pc_vault, false),
+        AccountMeta::new(*coin_wallet, false),
+        AccountMeta::new(*pc_wallet, false),
+        AccountMeta::new_readonly(*vault_signer, false),
+        AccountMeta::new_readonly(*spl_token_program_id, false),
+        AccountMeta::new_readonly(*fee_owner, false),
+    ];
+    Ok(encode(program_id, accounts, &data))
+}
+
+/// Constructs a `Swap` instruction: a single CPI-friendly round-trip that
+/// matches an IOC taker order and settles the proceeds straight to
+/// `destination_wallet`, without the caller ever having to hold a
+/// persistent `OpenOrders` account or call `SettleFunds` separately.
+///
+/// `open_orders` may be a scratch account created earlier in the same
+/// transaction, or an existing account the caller already owns for this
+/// market. There is currently no instruction for reclaiming a scratch
+/// account's rent; the caller is responsible for its lifetime.
+#[allow(clippy::too_many_arguments)]
+pub fn swap(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    open_orders: &Pubkey,
+    req_q: &Pubkey,
+    event_q: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    source_wallet: &Pubkey,
+    destination_wallet: &Pubkey,
+    source_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    pc_vault: &Pubkey,
+    vault_signer: &Pubkey,
+    spl_token_program_id: &Pubkey,
+    side: Side,
+    native_qty_in: u64,
+    min_expected_native_qty_out: u64,
+    limit: u16,
+) -> Result<Instruction, ProgramError> {
+    let data = MarketInstruction::Swap(SwapInstruction {
+        side,
+        native_qty_in,
+        min_expected_native_qty_out,
+        order_type: OrderType::ImmediateOrCancel,
+        limit,
+    });
+    let accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*open_orders, false),
+        AccountMeta::new(*req_q, false),
+        AccountMeta::new(*event_q, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new(*source_wallet, false),
+        AccountMeta::new(*destination_wallet, false),
+        AccountMeta::new_readonly(*source_owner, true),
+        AccountMeta::new(*coin_vault, false),
+        AccountMeta::new(*pc_vault, false),
+        AccountMeta::new_readonly(*vault_signer, false),
+        AccountMeta::new_readonly(*spl_token_program_id, false),
+    ];
+    Ok(encode(program_id, accounts, &data))
+}
+
+/// Constructs a `MatchOrders` instruction, crossing up to `limit` pairs of
+/// resting orders off the book and pushing the resulting fills onto
+/// `event_q` for later consumption.
+pub fn match_orders(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    req_q: &Pubkey,
+    event_q: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    limit: u16,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*req_q, false),
+        AccountMeta::new(*event_q, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+    ];
+    Ok(encode(program_id, accounts, &MarketInstruction::MatchOrders(limit)))
+}
+
+/// Constructs a `ConsumeEvents` instruction, draining up to `limit` fill
+/// events from `event_q` and crediting the named `open_orders_accounts`.
+pub fn consume_events(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    open_orders_accounts: &[Pubkey],
+    event_q: &Pubkey,
+    limit: u16,
+) -> Result<Instruction, ProgramError> {
+    let mut accounts: Vec<AccountMeta> = open_orders_accounts
+        .iter()
+        .map(|open_orders| AccountMeta::new(*open_orders, false))
+        .collect();
+    accounts.push(AccountMeta::new(*market, false));
+    accounts.push(AccountMeta::new(*event_q, false));
+    Ok(encode(program_id, accounts, &MarketInstruction::ConsumeEvents(limit)))
+}
+
+/// Constructs a `SettleFunds` instruction, sweeping `open_orders`'s free
+/// coin/pc balances out to the owner's wallet token accounts.
+#[allow(clippy::too_many_arguments)]
+pub fn settle_funds(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    open_orders: &Pubkey,
+    open_orders_owner: &Pubkey,
+    coin_vault: &Pubkey,
+    coin_wallet: &Pubkey,
+    pc_vault: &Pubkey,
+    pc_wallet: &Pubkey,
+    vault_signer: &Pubkey,
+    spl_token_program_id: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*open_orders, false),
+        AccountMeta::new_readonly(*open_orders_owner, true),
+        AccountMeta::new(*coin_vault, false),
+        AccountMeta::new(*pc_vault, false),
+        AccountMeta::new(*coin_wallet, false),
+        AccountMeta::new(*pc_wallet, false),
+        AccountMeta::new_readonly(*vault_signer, false),
+        AccountMeta::new_readonly(*spl_token_program_id, false),
+    ];
+    Ok(encode(program_id, accounts, &MarketInstruction::SettleFunds))
+}
+
+/// Constructs a `CancelOrder` instruction for an order the owner still has
+/// resting on the book.
+pub fn cancel_order(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    bids: &Pubkey,
+    asks: &Pubkey,
+    open_orders: &Pubkey,
+    open_orders_owner: &Pubkey,
+    side: Side,
+    order_id: u128,
+) -> Result<Instruction, ProgramError> {
+    let data = MarketInstruction::CancelOrder(CancelOrderInstruction { side, order_id });
+    let accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new(*bids, false),
+        AccountMeta::new(*asks, false),
+        AccountMeta::new(*open_orders, false),
+        AccountMeta::new_readonly(*open_orders_owner, true),
+    ];
+    Ok(encode(program_id, accounts, &data))
+}
+
+fn encode(program_id: &Pubkey, accounts: Vec<AccountMeta>, data: &MarketInstruction) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: pack(data),
+    }
+}
+
+/// Tag-prefixed little-endian encoding: a `u32` variant tag followed by
+/// the variant's fields in declaration order.
+fn pack(instruction: &MarketInstruction) -> Vec<u8> {
+    let mut data = Vec::new();
+    match instruction {
+        MarketInstruction::InitializeMarket(inner) => {
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&inner.coin_lot_size.to_le_bytes());
+            data.extend_from_slice(&inner.pc_lot_size.to_le_bytes());
+            data.extend_from_slice(&inner.fee_rate_bps.to_le_bytes());
+            data.extend_from_slice(&inner.vault_signer_nonce.to_le_bytes());
+            data.extend_from_slice(&inner.pc_dust_threshold.to_le_bytes());
+        }
+        MarketInstruction::NewOrderV3(inner) => {
+            data.extend_from_slice(&1u32.to_le_bytes());
+            data.push(inner.side as u8);
+            data.extend_from_slice(&inner.limit_price.get().to_le_bytes());
+            data.extend_from_slice(&inner.max_coin_qty.get().to_le_bytes());
+            data.extend_from_slice(&inner.max_native_pc_qty_including_fees.get().to_le_bytes());
+            data.push(inner.self_trade_behavior as u8);
+            data.push(inner.order_type as u8);
+            data.extend_from_slice(&inner.client_order_id.to_le_bytes());
+            data.extend_from_slice(&inner.limit.to_le_bytes());
+        }
+        MarketInstruction::SendTake(inner) => {
+            data.extend_from_slice(&2u32.to_le_bytes());
+            data.push(inner.side as u8);
+            data.extend_from_slice(&inner.limit_price.get().to_le_bytes());
+            data.extend_from_slice(&inner.max_coin_qty.get().to_le_bytes());
+            data.extend_from_slice(&inner.max_native_pc_qty_including_fees.get().to_le_bytes());
+            data.extend_from_slice(&inner.min_coin_qty.to_le_bytes());
+            data.extend_from_slice(&inner.min_native_pc_qty.to_le_bytes());
+            data.push(inner.self_trade_behavior as u8);
+            data.push(inner.order_type as u8);
+            data.extend_from_slice(&inner.client_order_id.to_le_bytes());
+            data.extend_from_slice(&inner.limit.to_le_bytes());
+        }
+        MarketInstruction::InitOpenOrders => data.extend_from_slice(&3u32.to_le_bytes()),
+        MarketInstruction::MatchOrders(limit) => {
+            data.extend_from_slice(&4u32.to_le_bytes());
+            data.extend_from_slice(&limit.to_le_bytes());
+        }
+        MarketInstruction::ConsumeEvents(limit) => {
+            data.extend_from_slice(&5u32.to_le_bytes());
+            data.extend_from_slice(&limit.to_le_bytes());
+        }
+        MarketInstruction::SettleFunds => data.extend_from_slice(&6u32.to_le_bytes()),
+        MarketInstruction::Swap(inner) => {
+            data.extend_from_slice(&7u32.to_le_bytes());
+            data.push(inner.side as u8);
+            data.extend_from_slice(&inner.native_qty_in.to_le_bytes());
+            data.extend_from_slice(&inner.min_expected_native_qty_out.to_le_bytes());
+            data.push(inner.order_type as u8);
+            data.extend_from_slice(&inner.limit.to_le_bytes());
+        }
+        MarketInstruction::CancelOrder(inner) => {
+            data.extend_from_slice(&8u32.to_le_bytes());
+            data.push(inner.side as u8);
+            data.extend_from_slice(&inner.order_id.to_le_bytes());
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(instruction: &MarketInstruction) -> u32 {
+        u32::from_le_bytes(pack(instruction)[0..4].try_into().unwrap())
+    }
+
+    /// Wire tags must never be renumbered once shipped — a client or
+    /// decoder built against an earlier layout would silently decode the
+    /// wrong instruction. New variants get the next free tag, appended at
+    /// the end, never inserted in the middle.
+    #[test]
+    fn wire_tags_are_stable_and_append_only() {
+        assert_eq!(
+            tag(&MarketInstruction::InitializeMarket(InitializeMarketInstruction {
+                coin_lot_size: 1,
+                pc_lot_size: 1,
+                fee_rate_bps: 0,
+                vault_signer_nonce: 0,
+                pc_dust_threshold: 0,
+            })),
+            0
+        );
+        assert_eq!(
+            tag(&MarketInstruction::NewOrderV3(NewOrderInstructionV3 {
+                side: Side::Bid,
+                limit_price: NonZeroU64::new(1).unwrap(),
+                max_coin_qty: NonZeroU64::new(1).unwrap(),
+                max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+                self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                order_type: OrderType::Limit,
+                client_order_id: 0,
+                limit: 0,
+            })),
+            1
+        );
+        assert_eq!(
+            tag(&MarketInstruction::SendTake(SendTakeInstruction {
+                side: Side::Bid,
+                limit_price: NonZeroU64::new(1).unwrap(),
+                max_coin_qty: NonZeroU64::new(1).unwrap(),
+                max_native_pc_qty_including_fees: NonZeroU64::new(1).unwrap(),
+                min_coin_qty: 0,
+                min_native_pc_qty: 0,
+                self_trade_behavior: SelfTradeBehavior::DecrementTake,
+                order_type: OrderType::ImmediateOrCancel,
+                client_order_id: 0,
+                limit: 0,
+            })),
+            2
+        );
+        assert_eq!(tag(&MarketInstruction::InitOpenOrders), 3);
+        assert_eq!(tag(&MarketInstruction::MatchOrders(0)), 4);
+        assert_eq!(tag(&MarketInstruction::ConsumeEvents(0)), 5);
+        assert_eq!(tag(&MarketInstruction::SettleFunds), 6);
+        assert_eq!(
+            tag(&MarketInstruction::Swap(SwapInstruction {
+                side: Side::Bid,
+                native_qty_in: 0,
+                min_expected_native_qty_out: 0,
+                order_type: OrderType::ImmediateOrCancel,
+                limit: 0,
+            })),
+            7
+        );
+        assert_eq!(
+            tag(&MarketInstruction::CancelOrder(CancelOrderInstruction {
+                side: Side::Bid,
+                order_id: 0,
+            })),
+            8
+        );
+    }
+}
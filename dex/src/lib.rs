@@ -0,0 +1,14 @@
+//! Serum DEX program: an on-chain central limit order book.
+
+pub mod error;
+pub mod fees;
+pub mod instruction;
+pub mod matching;
+pub mod state;
+
+/// Client/test-side market bootstrap helpers. Pulls in `solana-client`, so
+/// it's left out of on-chain program builds.
+#[cfg(not(feature = "program"))]
+pub mod testing;
+
+solana_program::declare_id!("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin");
@@ -0,0 +1,583 @@
+//! The order matching engine: book representation, order types, and the
+//! core `new_order` routine shared by every instruction that crosses the
+//! book (`NewOrderV3`, `SendTake`, and the `Swap` wrapper).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::num::NonZeroU64;
+
+use solana_program::pubkey::Pubkey;
+
+use crate::error::{DexError, DexErrorCode, DexResult};
+use crate::fees;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[repr(u8)]
+pub enum Side {
+    Bid = 0,
+    Ask = 1,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[repr(u8)]
+pub enum OrderType {
+    Limit = 0,
+    ImmediateOrCancel = 1,
+    PostOnly = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+#[repr(u8)]
+pub enum SelfTradeBehavior {
+    DecrementTake = 0,
+    CancelProvide = 1,
+    AbortTransaction = 2,
+}
+
+/// A single resting order, queued within its price level in FIFO order.
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub order_id: u128,
+    pub open_orders: Pubkey,
+    pub open_orders_slot: u8,
+    pub client_order_id: u64,
+    pub price: NonZeroU64,
+    pub coin_qty_remaining: u64,
+}
+
+/// Price-ordered book for one side of the market. Bids are iterated from
+/// the highest price down; asks from the lowest price up.
+#[derive(Default)]
+pub struct OrderBook {
+    pub levels: BTreeMap<u64, VecDeque<Order>>,
+}
+
+impl OrderBook {
+    pub fn best_price(&self, side: Side) -> Option<u64> {
+        match side {
+            Side::Bid => self.levels.keys().next_back().copied(),
+            Side::Ask => self.levels.keys().next().copied(),
+        }
+    }
+}
+
+/// Parameters for matching a single incoming order against the book.
+/// `is_send_take` marks orders placed via [`crate::instruction::MarketInstruction::SendTake`]:
+/// such orders must be fully satisfied (or rejected) within this
+/// instruction and are never allowed to rest on the book. The `Swap`
+/// wrapper does not rely on this flag; it settles whatever was matched.
+pub struct NewOrderParams {
+    pub side: Side,
+    pub order_type: OrderType,
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub limit_price: NonZeroU64,
+    pub max_coin_qty: NonZeroU64,
+    pub max_native_pc_qty_including_fees: NonZeroU64,
+    pub limit: u16,
+    pub owner: Pubkey,
+    pub owner_slot: u8,
+    pub client_order_id: u64,
+    pub is_send_take: bool,
+    /// Transfer-fee-extension rate (in basis points) configured on whichever
+    /// mint is being locked to fund this order: the pc mint for a `Bid`, the
+    /// coin mint for an `Ask`. Zero for legacy SPL Token mints and for
+    /// Token-2022 mints with no transfer-fee extension. The vault only ever
+    /// actually receives the post-fee amount, so matching must budget
+    /// against that net amount rather than the gross amount the caller
+    /// asked to lock.
+    pub input_transfer_fee_bps: u16,
+}
+
+/// The fee a Token-2022 transfer-fee-extension mint will deduct from a
+/// transfer of `gross`, at `fee_bps` basis points. Shared with `state.rs`,
+/// which needs the same figure to build a `TransferCheckedWithFee`
+/// instruction for vault payouts.
+pub(crate) fn transfer_fee_amount(gross: u64, fee_bps: u16) -> u64 {
+    if fee_bps == 0 {
+        return 0;
+    }
+    ((gross as u128 * fee_bps as u128 + 9_999) / 10_000) as u64
+}
+
+/// Returns the net amount the vault will actually receive after a
+/// Token-2022 transfer-fee-extension deduction of `fee_bps` basis points.
+fn net_of_transfer_fee(gross: u64, fee_bps: u16) -> DexResult<u64> {
+    gross
+        .checked_sub(transfer_fee_amount(gross, fee_bps))
+        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))
+}
+
+/// Accumulates the net effect of a single matching pass so the caller can
+/// decide how to settle it: credited into `OpenOrders` free balances for a
+/// resting/ordinary taker, or paid straight out to wallet accounts for
+/// `SendTake`/`Swap`.
+#[derive(Default, Debug)]
+pub struct RequestProceeds {
+    pub coin_credit: u64,
+    pub native_pc_credit: u64,
+    pub coin_debit: u64,
+    pub native_pc_debit: u64,
+    pub native_fee_paid: u64,
+    pub native_fee_rebate: u64,
+}
+
+impl RequestProceeds {
+    fn accumulate_fill(
+        &mut self,
+        side: Side,
+        native_pc_paid_or_received: u64,
+        coin_paid_or_received: u64,
+    ) -> DexResult<()> {
+        match side {
+            Side::Bid => {
+                self.coin_credit = self
+                    .coin_credit
+                    .checked_add(coin_paid_or_received)
+                    .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+                self.native_pc_debit = self
+                    .native_pc_debit
+                    .checked_add(native_pc_paid_or_received)
+                    .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+            }
+            Side::Ask => {
+                self.native_pc_credit = self
+                    .native_pc_credit
+                    .checked_add(native_pc_paid_or_received)
+                    .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+                self.coin_debit = self
+                    .coin_debit
+                    .checked_add(coin_paid_or_received)
+                    .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Holds mutable references to both sides of the book for the duration of
+/// a single instruction.
+pub struct OrderBookState<'a> {
+    pub bids: &'a mut OrderBook,
+    pub asks: &'a mut OrderBook,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    /// Mutable reference to the market's persisted `order_id_seq`, so every
+    /// order that rests on the book this instruction gets a fresh id.
+    pub order_id_seq: &'a mut u64,
+}
+
+impl<'a> OrderBookState<'a> {
+    fn opposite_side(side: Side) -> Side {
+        match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        }
+    }
+
+    fn crosses(side: Side, order_price: u64, level_price: u64) -> bool {
+        match side {
+            Side::Bid => order_price >= level_price,
+            Side::Ask => order_price <= level_price,
+        }
+    }
+
+    fn book_mut(&mut self, side: Side) -> &mut OrderBook {
+        match side {
+            Side::Bid => self.bids,
+            Side::Ask => self.asks,
+        }
+    }
+
+    /// Matches `params` against the resting side of the book, accumulating
+    /// fills into `proceeds`. Returns the unfilled remainder of a `Limit`
+    /// order, to be rested on the book, or `None` if nothing is left to
+    /// rest. For any other order type the remainder is discarded here and
+    /// `None` is returned. For a `SendTake` order (`is_send_take`), any
+    /// remainder is returned rather than rested on the book or discarded
+    /// here, so the caller can reject it instead of silently settling a
+    /// partial fill.
+    pub fn new_order(
+        &mut self,
+        params: NewOrderParams,
+        proceeds: &mut RequestProceeds,
+    ) -> DexResult<Option<Order>> {
+        let opposite = Self::opposite_side(params.side);
+        let mut coin_qty_remaining = params.max_coin_qty.get();
+        let mut native_pc_qty_remaining = params.max_native_pc_qty_including_fees.get();
+        match params.side {
+            Side::Ask => {
+                coin_qty_remaining = net_of_transfer_fee(coin_qty_remaining, params.input_transfer_fee_bps)?
+            }
+            Side::Bid => {
+                native_pc_qty_remaining =
+                    net_of_transfer_fee(native_pc_qty_remaining, params.input_transfer_fee_bps)?
+            }
+        }
+        let mut fills_left = params.limit;
+
+        while coin_qty_remaining > 0 && native_pc_qty_remaining > 0 && fills_left > 0 {
+            let best_price = match self.book_mut(opposite).best_price(opposite) {
+                Some(p) => p,
+                None => break,
+            };
+            if !Self::crosses(params.side, params.limit_price.get(), best_price) {
+                break;
+            }
+
+            let level = self.book_mut(opposite).levels.get_mut(&best_price).unwrap();
+            let maker_order = level.front_mut().unwrap();
+
+            if maker_order.open_orders == params.owner {
+                match params.self_trade_behavior {
+                    SelfTradeBehavior::AbortTransaction => {
+                        return Err(DexError::ErrorCode(DexErrorCode::WouldSelfTrade));
+                    }
+                    SelfTradeBehavior::CancelProvide => {
+                        level.pop_front();
+                        if level.is_empty() {
+                            self.book_mut(opposite).levels.remove(&best_price);
+                        }
+                        continue;
+                    }
+                    SelfTradeBehavior::DecrementTake => {
+                        let trade_qty = coin_qty_remaining.min(maker_order.coin_qty_remaining);
+                        maker_order.coin_qty_remaining -= trade_qty;
+                        coin_qty_remaining -= trade_qty;
+                        if maker_order.coin_qty_remaining == 0 {
+                            level.pop_front();
+                        }
+                        if level.is_empty() {
+                            self.book_mut(opposite).levels.remove(&best_price);
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let max_by_coin = coin_qty_remaining.min(maker_order.coin_qty_remaining);
+            let native_pc_at_price = max_by_coin
+                .checked_mul(best_price)
+                .and_then(|v| v.checked_mul(self.pc_lot_size))
+                .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+            let max_by_pc = native_pc_qty_remaining / best_price.max(1) * self.pc_lot_size.max(1);
+            let trade_qty = if native_pc_at_price <= native_pc_qty_remaining {
+                max_by_coin
+            } else {
+                max_by_coin.min(max_by_pc)
+            };
+            if trade_qty == 0 {
+                break;
+            }
+
+            let native_pc_traded = trade_qty
+                .checked_mul(best_price)
+                .and_then(|v| v.checked_mul(self.pc_lot_size))
+                .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+            let fee = fees::taker_fee(native_pc_traded);
+            let rebate = fees::maker_rebate(native_pc_traded);
+
+            match params.side {
+                Side::Bid => {
+                    let native_pc_debited = native_pc_traded
+                        .checked_add(fee)
+                        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+                    proceeds.accumulate_fill(Side::Bid, native_pc_debited, trade_qty)?;
+                    native_pc_qty_remaining = native_pc_qty_remaining.saturating_sub(native_pc_debited);
+                }
+                Side::Ask => {
+                    let native_pc_credited = native_pc_traded
+                        .checked_sub(fee)
+                        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+                    proceeds.accumulate_fill(Side::Ask, native_pc_credited, trade_qty)?;
+                }
+            }
+            proceeds.native_fee_paid = proceeds
+                .native_fee_paid
+                .checked_add(fee)
+                .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+            proceeds.native_fee_rebate = proceeds
+                .native_fee_rebate
+                .checked_add(rebate)
+                .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+
+            coin_qty_remaining -= trade_qty;
+            maker_order.coin_qty_remaining -= trade_qty;
+            if maker_order.coin_qty_remaining == 0 {
+                level.pop_front();
+            }
+            if level.is_empty() {
+                self.book_mut(opposite).levels.remove(&best_price);
+            }
+            fills_left -= 1;
+        }
+
+        let can_rest = coin_qty_remaining > 0
+            && params.order_type == OrderType::Limit
+            && !params.is_send_take;
+
+        if params.is_send_take && coin_qty_remaining > 0 {
+            // `SendTake` orders must never leave resting state
+            // behind. Surface the residual to the caller rather than
+            // silently discarding it, so `process_send_take` can reject it
+            // via `TakeOnlyOrderLeftResting`. This `Order` is never
+            // inserted into the book and never allocated a real
+            // `order_id`.
+            return Ok(Some(Order {
+                order_id: 0,
+                open_orders: params.owner,
+                open_orders_slot: params.owner_slot,
+                client_order_id: params.client_order_id,
+                price: params.limit_price,
+                coin_qty_remaining,
+            }));
+        }
+
+        if !can_rest {
+            return Ok(None);
+        }
+
+        *self.order_id_seq = self
+            .order_id_seq
+            .checked_add(1)
+            .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+
+        Ok(Some(Order {
+            order_id: *self.order_id_seq as u128,
+            open_orders: params.owner,
+            open_orders_slot: params.owner_slot,
+            client_order_id: params.client_order_id,
+            price: params.limit_price,
+            coin_qty_remaining,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nz(n: u64) -> NonZeroU64 {
+        NonZeroU64::new(n).unwrap()
+    }
+
+    fn resting_ask(owner: Pubkey, price: u64, coin_qty: u64) -> Order {
+        Order {
+            order_id: 0,
+            open_orders: owner,
+            open_orders_slot: 0,
+            client_order_id: 0,
+            price: nz(price),
+            coin_qty_remaining: coin_qty,
+        }
+    }
+
+    fn taker_bid(owner: Pubkey, is_send_take: bool) -> NewOrderParams {
+        NewOrderParams {
+            side: Side::Bid,
+            order_type: OrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            limit_price: nz(10),
+            max_coin_qty: nz(100),
+            max_native_pc_qty_including_fees: nz(10_000),
+            limit: 65535,
+            owner,
+            owner_slot: 1,
+            client_order_id: 0,
+            is_send_take,
+            input_transfer_fee_bps: 0,
+        }
+    }
+
+    #[test]
+    fn partial_fill_leaves_the_remainder_unfilled() {
+        let maker = Pubkey::new_unique();
+        let taker = Pubkey::new_unique();
+        let mut asks = OrderBook::default();
+        asks.levels
+            .entry(10)
+            .or_default()
+            .push_back(resting_ask(maker, 10, 40));
+        let mut bids = OrderBook::default();
+
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+        let mut params = taker_bid(taker, false);
+        params.order_type = OrderType::Limit;
+        let mut proceeds = RequestProceeds::default();
+        let remaining = book.new_order(params, &mut proceeds).unwrap();
+
+        assert_eq!(proceeds.coin_credit, 40);
+        assert!(book.asks.levels.is_empty());
+        let remaining = remaining.expect("unfilled balance should rest on the book");
+        assert_eq!(remaining.coin_qty_remaining, 60);
+    }
+
+    #[test]
+    fn self_trade_decrement_take_consumes_both_sides_without_a_fill() {
+        let owner = Pubkey::new_unique();
+        let mut asks = OrderBook::default();
+        asks.levels
+            .entry(10)
+            .or_default()
+            .push_back(resting_ask(owner, 10, 40));
+        let mut bids = OrderBook::default();
+
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+        let mut params = taker_bid(owner, false);
+        params.order_type = OrderType::Limit;
+        let mut proceeds = RequestProceeds::default();
+        let remaining = book.new_order(params, &mut proceeds).unwrap();
+
+        assert_eq!(proceeds.coin_credit, 0, "self-trades must not generate a fill");
+        assert!(book.asks.levels.is_empty());
+        assert_eq!(remaining.unwrap().coin_qty_remaining, 60);
+    }
+
+    #[test]
+    fn self_trade_abort_transaction_errors() {
+        let owner = Pubkey::new_unique();
+        let mut asks = OrderBook::default();
+        asks.levels
+            .entry(10)
+            .or_default()
+            .push_back(resting_ask(owner, 10, 40));
+        let mut bids = OrderBook::default();
+
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+        let mut params = taker_bid(owner, false);
+        params.self_trade_behavior = SelfTradeBehavior::AbortTransaction;
+        let mut proceeds = RequestProceeds::default();
+
+        let err = book.new_order(params, &mut proceeds).unwrap_err();
+        assert!(matches!(
+            err,
+            DexError::ErrorCode(DexErrorCode::WouldSelfTrade)
+        ));
+    }
+
+    #[test]
+    fn send_take_style_order_never_rests() {
+        let maker = Pubkey::new_unique();
+        let taker = Pubkey::new_unique();
+        let mut asks = OrderBook::default();
+        asks.levels
+            .entry(10)
+            .or_default()
+            .push_back(resting_ask(maker, 10, 40));
+        let mut bids = OrderBook::default();
+
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+        let mut proceeds = RequestProceeds::default();
+        let remaining = book.new_order(taker_bid(taker, true), &mut proceeds).unwrap();
+
+        assert_eq!(proceeds.coin_credit, 40);
+        assert!(book.bids.levels.is_empty(), "a SendTake/Swap order must never rest on the book");
+        assert_eq!(
+            remaining.expect("the unfilled residual must be surfaced, not silently dropped").coin_qty_remaining,
+            60,
+            "process_send_take relies on this to reject a partial fill via TakeOnlyOrderLeftResting"
+        );
+    }
+
+    #[test]
+    fn resting_orders_get_distinct_sequential_order_ids() {
+        let taker_a = Pubkey::new_unique();
+        let taker_b = Pubkey::new_unique();
+        let mut asks = OrderBook::default();
+        let mut bids = OrderBook::default();
+        let mut order_id_seq = 0u64;
+
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut order_id_seq,
+        };
+
+        let mut params_a = taker_bid(taker_a, false);
+        params_a.order_type = OrderType::Limit;
+        let mut proceeds = RequestProceeds::default();
+        let resting_a = book
+            .new_order(params_a, &mut proceeds)
+            .unwrap()
+            .expect("empty book: the bid should rest in full");
+
+        let mut params_b = taker_bid(taker_b, false);
+        params_b.order_type = OrderType::Limit;
+        let resting_b = book
+            .new_order(params_b, &mut proceeds)
+            .unwrap()
+            .expect("empty opposite side: the second bid should also rest in full");
+
+        assert_ne!(
+            resting_a.order_id, resting_b.order_id,
+            "two resting orders on the same market must not collide on order_id"
+        );
+        assert_eq!(resting_a.order_id, 1);
+        assert_eq!(resting_b.order_id, 2);
+    }
+
+    #[test]
+    fn transfer_fee_mint_budgets_against_the_post_fee_amount() {
+        let maker = Pubkey::new_unique();
+        let taker = Pubkey::new_unique();
+        // A deep ask book and a generous coin budget so the pc budget, not
+        // the book or the coin side, is the binding constraint.
+        let mut asks = OrderBook::default();
+        asks.levels
+            .entry(10)
+            .or_default()
+            .push_back(resting_ask(maker, 10, 10_000));
+        let mut bids = OrderBook::default();
+
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+        let mut params = taker_bid(taker, false);
+        params.max_coin_qty = nz(10_000);
+        // A 1% (100 bps) transfer fee on the pc mint means only 9,900 of the
+        // nominal 10,000 native pc actually lands in the vault to spend.
+        params.input_transfer_fee_bps = 100;
+        let mut proceeds = RequestProceeds::default();
+        book.new_order(params, &mut proceeds).unwrap();
+
+        // 9,900 native pc buys 990 coin at a price of 10; the taker fee on
+        // top of that fill is charged in addition to the post-fee budget.
+        assert_eq!(proceeds.coin_credit, 990);
+        assert_eq!(proceeds.native_pc_debit, 9_922);
+    }
+
+    #[test]
+    fn net_of_transfer_fee_rounds_the_fee_up() {
+        assert_eq!(net_of_transfer_fee(1_000, 0).unwrap(), 1_000);
+        assert_eq!(net_of_transfer_fee(1_000, 100).unwrap(), 990);
+        assert_eq!(net_of_transfer_fee(1, 1).unwrap(), 0);
+    }
+}
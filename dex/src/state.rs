@@ -0,0 +1,743 @@
+//! On-chain account layouts (`MarketState`, `OpenOrders`) and the
+//! instruction handlers that operate on them.
+
+use std::str::FromStr;
+
+use solana_program::account_info::AccountInfo;
+use solana_program::program::invoke_signed;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+
+use crate::error::{check_assert, DexError, DexErrorCode, DexResult};
+use crate::matching::{transfer_fee_amount, NewOrderParams, Order, OrderBookState, RequestProceeds, Side};
+
+/// Returns the Token-2022 program id, `TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb`.
+pub fn token_2022_program_id() -> Pubkey {
+    Pubkey::from_str("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb")
+        .expect("hardcoded Token-2022 program id is valid")
+}
+
+/// Determines which token program owns a mint or vault account, so CPIs
+/// can be routed through the legacy `spl_token` program or Token-2022
+/// depending on what the market was actually listed with.
+pub fn detect_token_program(account: &AccountInfo) -> DexResult<Pubkey> {
+    let owner = *account.owner;
+    if owner == spl_token::id() || owner == token_2022_program_id() {
+        Ok(owner)
+    } else {
+        Err(DexError::ErrorCode(DexErrorCode::WrongTokenProgram))
+    }
+}
+
+/// Market-wide configuration and bookkeeping, persisted in the market
+/// account's data.
+#[derive(Debug)]
+pub struct MarketState {
+    pub own_address: Pubkey,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub vault_signer_nonce: u64,
+    pub pc_dust_threshold: u64,
+    pub coin_token_program: Pubkey,
+    pub pc_token_program: Pubkey,
+    /// Monotonically increasing counter handed out as the `order_id` of
+    /// every order that rests on the book, so `CancelOrder` can identify
+    /// one resting order among many. Persists for the lifetime of the
+    /// market; never reused, even across orders that are later filled or
+    /// cancelled.
+    pub order_id_seq: u64,
+}
+
+/// Per-user account tracking working orders and settleable balances for a
+/// single market.
+#[derive(Debug, Default)]
+pub struct OpenOrders {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub native_coin_free: u64,
+    pub native_coin_total: u64,
+    pub native_pc_free: u64,
+    pub native_pc_total: u64,
+    pub orders: Vec<Order>,
+}
+
+impl OpenOrders {
+    pub fn new(market: Pubkey, owner: Pubkey) -> Self {
+        OpenOrders {
+            market,
+            owner,
+            ..OpenOrders::default()
+        }
+    }
+
+    fn credit_coin(&mut self, native_qty: u64) -> DexResult<()> {
+        self.native_coin_free = self
+            .native_coin_free
+            .checked_add(native_qty)
+            .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+        self.native_coin_total = self
+            .native_coin_total
+            .checked_add(native_qty)
+            .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+        Ok(())
+    }
+
+    fn credit_pc(&mut self, native_qty: u64) -> DexResult<()> {
+        self.native_pc_free = self
+            .native_pc_free
+            .checked_add(native_qty)
+            .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+        self.native_pc_total = self
+            .native_pc_total
+            .checked_add(native_qty)
+            .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+        Ok(())
+    }
+}
+
+/// Derives the PDA that is granted authority over a market's token vaults.
+/// `nonce` is chosen by the market creator such that the resulting address
+/// falls off the ed25519 curve; `initialize_market` records the winning
+/// nonce so it can be reproduced here on every subsequent instruction.
+pub fn gen_vault_signer_key(
+    nonce: u64,
+    market: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<Pubkey, ProgramError> {
+    let seeds = [market.as_ref(), &nonce.to_le_bytes()];
+    Pubkey::create_program_address(&seeds, program_id).map_err(|_| ProgramError::InvalidSeeds)
+}
+
+/// Initializes a market. `coin_mint_account`/`pc_mint_account` and
+/// `coin_vault_account`/`pc_vault_account` are used only to determine which
+/// token program (legacy `spl_token` or Token-2022) each side of the market
+/// was listed under; a mint and its vault must agree, since a vault can
+/// only ever be owned by the program that also owns its mint.
+pub fn process_initialize_market(
+    market_account: &AccountInfo,
+    coin_mint_account: &AccountInfo,
+    pc_mint_account: &AccountInfo,
+    coin_vault_account: &AccountInfo,
+    pc_vault_account: &AccountInfo,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    vault_signer_nonce: u64,
+    pc_dust_threshold: u64,
+) -> DexResult<MarketState> {
+    let coin_token_program = detect_token_program(coin_mint_account)?;
+    let pc_token_program = detect_token_program(pc_mint_account)?;
+    check_assert(
+        *coin_vault_account.owner == coin_token_program,
+        DexErrorCode::WrongTokenProgram,
+    )?;
+    check_assert(
+        *pc_vault_account.owner == pc_token_program,
+        DexErrorCode::WrongTokenProgram,
+    )?;
+
+    Ok(MarketState {
+        own_address: *market_account.key,
+        coin_mint: *coin_mint_account.key,
+        pc_mint: *pc_mint_account.key,
+        coin_vault: *coin_vault_account.key,
+        pc_vault: *pc_vault_account.key,
+        coin_lot_size,
+        pc_lot_size,
+        vault_signer_nonce,
+        pc_dust_threshold,
+        coin_token_program,
+        pc_token_program,
+        order_id_seq: 0,
+    })
+}
+
+pub fn process_init_open_orders(market: &Pubkey, owner: &Pubkey) -> OpenOrders {
+    OpenOrders::new(*market, *owner)
+}
+
+/// Matches an incoming order and credits any resulting fills into the
+/// taker's `OpenOrders` free balances. This is the
+/// ordinary `NewOrderV3` path: proceeds stay on deposit until the user
+/// calls `settle_funds`.
+pub fn process_new_order(
+    book: &mut OrderBookState,
+    open_orders: &mut OpenOrders,
+    params: NewOrderParams,
+) -> DexResult<(Option<Order>, RequestProceeds)> {
+    let mut proceeds = RequestProceeds::default();
+    let remaining = book.new_order(params, &mut proceeds)?;
+    if proceeds.coin_credit > 0 {
+        open_orders.credit_coin(proceeds.coin_credit)?;
+    }
+    if proceeds.native_pc_credit > 0 {
+        open_orders.credit_pc(proceeds.native_pc_credit)?;
+    }
+    Ok((remaining, proceeds))
+}
+
+/// Per-instruction parameters for `SendTake`: a `NewOrderV3`-equivalent
+/// taker order whose proceeds are paid out directly to wallet token
+/// accounts rather than accrued into `OpenOrders`.
+pub struct SendTakeParams<'a> {
+    pub order: NewOrderParams,
+    pub min_coin_qty: u64,
+    pub min_native_pc_qty: u64,
+    pub coin_vault: &'a AccountInfo<'a>,
+    pub pc_vault: &'a AccountInfo<'a>,
+    pub coin_wallet: &'a AccountInfo<'a>,
+    pub pc_wallet: &'a AccountInfo<'a>,
+    pub coin_mint: &'a AccountInfo<'a>,
+    pub pc_mint: &'a AccountInfo<'a>,
+    pub coin_decimals: u8,
+    pub pc_decimals: u8,
+    pub coin_output_transfer_fee_bps: u16,
+    pub pc_output_transfer_fee_bps: u16,
+    pub vault_signer: &'a AccountInfo<'a>,
+    pub vault_signer_seeds: &'a [&'a [u8]],
+    pub coin_token_program: &'a AccountInfo<'a>,
+    pub pc_token_program: &'a AccountInfo<'a>,
+}
+
+/// Builds the checked-transfer instruction `token_transfer` will invoke:
+/// `TransferCheckedWithFee` when `transfer_fee_bps` implies a nonzero fee on
+/// `amount`, `TransferChecked` otherwise. Pulled out of `token_transfer` so
+/// the choice between the two wire formats can be exercised without
+/// standing up real token accounts.
+#[allow(clippy::too_many_arguments)]
+fn build_transfer_instruction(
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    source: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+    transfer_fee_bps: u16,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    let expected_fee = transfer_fee_amount(amount, transfer_fee_bps);
+    if expected_fee > 0 {
+        spl_token_2022::extension::transfer_fee::instruction::transfer_checked_with_fee(
+            token_program,
+            source,
+            mint,
+            destination,
+            authority,
+            &[],
+            amount,
+            decimals,
+            expected_fee,
+        )
+    } else {
+        spl_token_2022::instruction::transfer_checked(
+            token_program,
+            source,
+            mint,
+            destination,
+            authority,
+            &[],
+            amount,
+            decimals,
+        )
+    }
+}
+
+/// Invokes a checked token transfer through whichever token program
+/// actually owns `source`/`destination`, so the same call site works
+/// whether the mint is legacy `spl_token` or Token-2022. Uses
+/// `TransferChecked` (or, when `transfer_fee_bps` is nonzero,
+/// `TransferCheckedWithFee`) rather than the bare `Transfer` instruction:
+/// Token-2022 rejects unchecked transfers against a mint carrying an
+/// extension that requires the checked form — including
+/// `TransferFeeConfig`, which is exactly the extension this module exists
+/// to support.
+#[allow(clippy::too_many_arguments)]
+fn token_transfer<'a>(
+    token_program: &AccountInfo<'a>,
+    mint: &AccountInfo<'a>,
+    source: &AccountInfo<'a>,
+    destination: &AccountInfo<'a>,
+    authority: &AccountInfo<'a>,
+    amount: u64,
+    decimals: u8,
+    transfer_fee_bps: u16,
+    signer_seeds: &[&[u8]],
+) -> DexResult<()> {
+    let instruction = build_transfer_instruction(
+        token_program.key,
+        mint.key,
+        source.key,
+        destination.key,
+        authority.key,
+        amount,
+        decimals,
+        transfer_fee_bps,
+    )?;
+    invoke_signed(
+        &instruction,
+        &[
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            authority.clone(),
+            token_program.clone(),
+        ],
+        &[signer_seeds],
+    )?;
+    Ok(())
+}
+
+/// Matches a `SendTake` order against the book and transfers the unlocked
+/// coin/pc straight to the caller's wallet accounts in the same
+/// instruction, instead of crediting `OpenOrders.native_{coin,pc}_free`.
+/// Any residual that would otherwise rest on the book is rejected, as is a
+/// realized fill below the caller's slippage floor.
+pub fn process_send_take(book: &mut OrderBookState, params: SendTakeParams) -> DexResult<RequestProceeds> {
+    check_assert(params.order.is_send_take, DexErrorCode::InvalidMarketFlags)?;
+
+    let mut proceeds = RequestProceeds::default();
+    let leftover = book.new_order(params.order, &mut proceeds)?;
+    check_assert(leftover.is_none(), DexErrorCode::TakeOnlyOrderLeftResting)?;
+
+    check_slippage_floor(&proceeds, params.min_coin_qty, params.min_native_pc_qty)?;
+
+    if proceeds.coin_credit > 0 {
+        token_transfer(
+            params.coin_token_program,
+            params.coin_mint,
+            params.coin_vault,
+            params.coin_wallet,
+            params.vault_signer,
+            proceeds.coin_credit,
+            params.coin_decimals,
+            params.coin_output_transfer_fee_bps,
+            params.vault_signer_seeds,
+        )?;
+    }
+    if proceeds.native_pc_credit > 0 {
+        token_transfer(
+            params.pc_token_program,
+            params.pc_mint,
+            params.pc_vault,
+            params.pc_wallet,
+            params.vault_signer,
+            proceeds.native_pc_credit,
+            params.pc_decimals,
+            params.pc_output_transfer_fee_bps,
+            params.vault_signer_seeds,
+        )?;
+    }
+
+    Ok(proceeds)
+}
+
+/// Per-instruction parameters for `Swap`: a complete market-order
+/// round-trip (match, then settle) against either a transient `OpenOrders`
+/// scratch account created earlier in the same transaction, or a
+/// pre-owned one supplied by the caller.
+pub struct SwapParams<'a> {
+    pub order: NewOrderParams,
+    pub min_expected_out: u64,
+    pub coin_vault: &'a AccountInfo<'a>,
+    pub pc_vault: &'a AccountInfo<'a>,
+    pub coin_wallet: &'a AccountInfo<'a>,
+    pub pc_wallet: &'a AccountInfo<'a>,
+    pub coin_mint: &'a AccountInfo<'a>,
+    pub pc_mint: &'a AccountInfo<'a>,
+    pub coin_decimals: u8,
+    pub pc_decimals: u8,
+    pub coin_output_transfer_fee_bps: u16,
+    pub pc_output_transfer_fee_bps: u16,
+    pub vault_signer: &'a AccountInfo<'a>,
+    pub vault_signer_seeds: &'a [&'a [u8]],
+    pub coin_token_program: &'a AccountInfo<'a>,
+    pub pc_token_program: &'a AccountInfo<'a>,
+}
+
+/// Matches `params.order` against the book via `open_orders`, then
+/// immediately settles the resulting free balances out to the caller's
+/// source/destination wallets, so integrators never have to manage a
+/// persistent `OpenOrders` account or issue a separate `SettleFunds`
+/// instruction. Returns `(native_qty_in, native_qty_out)`.
+pub fn process_swap(
+    book: &mut OrderBookState,
+    open_orders: &mut OpenOrders,
+    params: SwapParams,
+) -> DexResult<(u64, u64)> {
+    let side = params.order.side;
+
+    // A pre-owned `open_orders` may already be carrying free balance from
+    // earlier, unrelated fills (the `swap()` doc comment explicitly allows
+    // a caller to reuse one). Snapshot it here so only the delta this call
+    // actually credits gets paid out and checked against `min_expected_out`
+    // -- the stale balance is left exactly as it was, for the caller to
+    // settle separately.
+    let coin_free_before = open_orders.native_coin_free;
+    let pc_free_before = open_orders.native_pc_free;
+
+    let (_, proceeds) = process_new_order(book, open_orders, params.order)?;
+
+    let coin_out = open_orders
+        .native_coin_free
+        .checked_sub(coin_free_before)
+        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+    let pc_out = open_orders
+        .native_pc_free
+        .checked_sub(pc_free_before)
+        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+    open_orders.native_coin_free = coin_free_before;
+    open_orders.native_coin_total = open_orders
+        .native_coin_total
+        .checked_sub(coin_out)
+        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+    open_orders.native_pc_free = pc_free_before;
+    open_orders.native_pc_total = open_orders
+        .native_pc_total
+        .checked_sub(pc_out)
+        .ok_or(DexError::ErrorCode(DexErrorCode::Overflow))?;
+
+    let (native_qty_in, native_qty_out) = swap_amounts(side, &proceeds, coin_out, pc_out);
+    check_assert(
+        native_qty_out >= params.min_expected_out,
+        DexErrorCode::SlippageOutsideTolerance,
+    )?;
+
+    if coin_out > 0 {
+        token_transfer(
+            params.coin_token_program,
+            params.coin_mint,
+            params.coin_vault,
+            params.coin_wallet,
+            params.vault_signer,
+            coin_out,
+            params.coin_decimals,
+            params.coin_output_transfer_fee_bps,
+            params.vault_signer_seeds,
+        )?;
+    }
+    if pc_out > 0 {
+        token_transfer(
+            params.pc_token_program,
+            params.pc_mint,
+            params.pc_vault,
+            params.pc_wallet,
+            params.vault_signer,
+            pc_out,
+            params.pc_decimals,
+            params.pc_output_transfer_fee_bps,
+            params.vault_signer_seeds,
+        )?;
+    }
+
+    Ok((native_qty_in, native_qty_out))
+}
+
+/// Derives `process_swap`'s `(native_qty_in, native_qty_out)` from what was
+/// actually matched, not from the order's `max_coin_qty`/
+/// `max_native_pc_qty_including_fees` cap — on a partial fill the realized
+/// amounts are smaller than the cap. Pulled out of `process_swap` so it can
+/// be exercised without standing up real token accounts.
+fn swap_amounts(side: Side, proceeds: &RequestProceeds, coin_out: u64, pc_out: u64) -> (u64, u64) {
+    let native_qty_in = match side {
+        Side::Bid => proceeds.native_pc_debit,
+        Side::Ask => proceeds.coin_debit,
+    };
+    let native_qty_out = match side {
+        Side::Bid => coin_out,
+        Side::Ask => pc_out,
+    };
+    (native_qty_in, native_qty_out)
+}
+
+/// Rejects a realized fill that falls short of the caller's slippage
+/// floor. Pulled out of `process_send_take` so it can be exercised without
+/// standing up real token accounts.
+fn check_slippage_floor(
+    proceeds: &RequestProceeds,
+    min_coin_qty: u64,
+    min_native_pc_qty: u64,
+) -> DexResult<()> {
+    check_assert(
+        proceeds.coin_credit >= min_coin_qty,
+        DexErrorCode::SlippageOutsideTolerance,
+    )?;
+    check_assert(
+        proceeds.native_pc_credit >= min_native_pc_qty,
+        DexErrorCode::SlippageOutsideTolerance,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+
+    use crate::matching::{OrderBook, OrderType, SelfTradeBehavior};
+
+    use super::*;
+
+    fn nz(n: u64) -> NonZeroU64 {
+        NonZeroU64::new(n).unwrap()
+    }
+
+    #[test]
+    fn transfer_fee_mint_credits_the_post_fee_amount_into_open_orders() {
+        let maker = Pubkey::new_unique();
+        let taker = Pubkey::new_unique();
+
+        let mut asks = OrderBook::default();
+        asks.levels.entry(10).or_default().push_back(Order {
+            order_id: 0,
+            open_orders: maker,
+            open_orders_slot: 0,
+            client_order_id: 0,
+            price: nz(10),
+            coin_qty_remaining: 10_000,
+        });
+        let mut bids = OrderBook::default();
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+
+        let params = NewOrderParams {
+            side: Side::Bid,
+            order_type: OrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            limit_price: nz(10),
+            max_coin_qty: nz(10_000),
+            max_native_pc_qty_including_fees: nz(10_000),
+            limit: 65535,
+            owner: taker,
+            owner_slot: 1,
+            client_order_id: 0,
+            is_send_take: false,
+            input_transfer_fee_bps: 100,
+        };
+
+        let mut open_orders = OpenOrders::new(Pubkey::new_unique(), taker);
+        let (remaining, proceeds) = process_new_order(&mut book, &mut open_orders, params).unwrap();
+
+        assert!(remaining.is_none());
+        assert_eq!(proceeds.coin_credit, 990);
+        assert_eq!(open_orders.native_coin_free, 990);
+        assert_eq!(open_orders.native_coin_total, 990);
+        assert_eq!(open_orders.native_pc_free, 0);
+        assert_eq!(open_orders.native_pc_total, 0);
+    }
+
+    #[test]
+    fn swap_reports_the_native_qty_actually_matched_on_a_partial_fill() {
+        let maker = Pubkey::new_unique();
+        let taker = Pubkey::new_unique();
+
+        let mut asks = OrderBook::default();
+        asks.levels.entry(10).or_default().push_back(Order {
+            order_id: 0,
+            open_orders: maker,
+            open_orders_slot: 0,
+            client_order_id: 0,
+            price: nz(10),
+            coin_qty_remaining: 40,
+        });
+        let mut bids = OrderBook::default();
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+
+        let params = NewOrderParams {
+            side: Side::Bid,
+            order_type: OrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            limit_price: nz(10),
+            max_coin_qty: nz(1_000),
+            max_native_pc_qty_including_fees: nz(10_000),
+            limit: 65535,
+            owner: taker,
+            owner_slot: 1,
+            client_order_id: 0,
+            is_send_take: false,
+            input_transfer_fee_bps: 0,
+        };
+
+        let mut open_orders = OpenOrders::new(Pubkey::new_unique(), taker);
+        let (_, proceeds) = process_new_order(&mut book, &mut open_orders, params).unwrap();
+
+        let (native_qty_in, native_qty_out) = swap_amounts(
+            Side::Bid,
+            &proceeds,
+            open_orders.native_coin_free,
+            open_orders.native_pc_free,
+        );
+
+        assert_eq!(native_qty_out, 40, "only the resting 40 coin should be reported, not the 1_000 cap");
+        assert_eq!(native_qty_in, proceeds.native_pc_debit);
+        assert!(
+            native_qty_in < 10_000,
+            "native_qty_in must reflect the partial fill, not the order's pc cap"
+        );
+    }
+
+    #[test]
+    fn slippage_floor_rejects_a_fill_below_the_minimum() {
+        let proceeds = RequestProceeds {
+            coin_credit: 40,
+            ..RequestProceeds::default()
+        };
+
+        let err = check_slippage_floor(&proceeds, 50, 0).unwrap_err();
+        assert!(matches!(
+            err,
+            DexError::ErrorCode(DexErrorCode::SlippageOutsideTolerance)
+        ));
+    }
+
+    #[test]
+    fn slippage_floor_accepts_a_fill_at_or_above_the_minimum() {
+        let proceeds = RequestProceeds {
+            coin_credit: 50,
+            native_pc_credit: 0,
+            ..RequestProceeds::default()
+        };
+
+        assert!(check_slippage_floor(&proceeds, 50, 0).is_ok());
+    }
+
+    #[test]
+    fn swap_delta_excludes_stale_free_balance_left_over_from_earlier_fills() {
+        let maker = Pubkey::new_unique();
+        let taker = Pubkey::new_unique();
+
+        let mut asks = OrderBook::default();
+        asks.levels.entry(10).or_default().push_back(Order {
+            order_id: 0,
+            open_orders: maker,
+            open_orders_slot: 0,
+            client_order_id: 0,
+            price: nz(10),
+            coin_qty_remaining: 40,
+        });
+        let mut bids = OrderBook::default();
+        let mut book = OrderBookState {
+            bids: &mut bids,
+            asks: &mut asks,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            order_id_seq: &mut 0,
+        };
+
+        let params = NewOrderParams {
+            side: Side::Bid,
+            order_type: OrderType::ImmediateOrCancel,
+            self_trade_behavior: SelfTradeBehavior::DecrementTake,
+            limit_price: nz(10),
+            max_coin_qty: nz(1_000),
+            max_native_pc_qty_including_fees: nz(10_000),
+            limit: 65535,
+            owner: taker,
+            owner_slot: 1,
+            client_order_id: 0,
+            is_send_take: false,
+            input_transfer_fee_bps: 0,
+        };
+
+        // Simulate a pre-owned `open_orders` already carrying free balance
+        // from an earlier, unrelated fill.
+        let mut open_orders = OpenOrders::new(Pubkey::new_unique(), taker);
+        open_orders.credit_coin(500).unwrap();
+
+        let coin_free_before = open_orders.native_coin_free;
+        let (_, proceeds) = process_new_order(&mut book, &mut open_orders, params).unwrap();
+        let coin_out = open_orders.native_coin_free.checked_sub(coin_free_before).unwrap();
+
+        let (_, native_qty_out) = swap_amounts(Side::Bid, &proceeds, coin_out, open_orders.native_pc_free);
+
+        assert_eq!(coin_out, 40, "only this call's fill should count, not the pre-existing 500");
+        assert_eq!(native_qty_out, 40);
+    }
+
+    #[test]
+    fn vault_payout_on_a_transfer_fee_mint_uses_transfer_checked_with_fee() {
+        let token_program = token_2022_program_id();
+        let mint = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+
+        let instruction =
+            build_transfer_instruction(&token_program, &mint, &source, &destination, &authority, 10_000, 6, 100)
+                .unwrap();
+
+        // `TransferCheckedWithFee` is Token-2022's transfer-fee-extension
+        // instruction, distinct from the base `TransferChecked` tag; a
+        // transfer-fee mint rejects the base `Transfer`/`TransferChecked`
+        // forms once the extension is active.
+        let expected = spl_token_2022::extension::transfer_fee::instruction::transfer_checked_with_fee(
+            &token_program,
+            &source,
+            &mint,
+            &destination,
+            &authority,
+            &[],
+            10_000,
+            6,
+            transfer_fee_amount(10_000, 100),
+        )
+        .unwrap();
+        assert_eq!(instruction.data, expected.data);
+    }
+
+    #[test]
+    fn vault_payout_on_a_fee_free_mint_uses_plain_transfer_checked() {
+        let token_program = token_2022_program_id();
+        let mint = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+
+        let instruction =
+            build_transfer_instruction(&token_program, &mint, &source, &destination, &authority, 10_000, 6, 0)
+                .unwrap();
+
+        let expected = spl_token_2022::instruction::transfer_checked(
+            &token_program,
+            &source,
+            &mint,
+            &destination,
+            &authority,
+            &[],
+            10_000,
+            6,
+        )
+        .unwrap();
+        assert_eq!(instruction.data, expected.data);
+    }
+
+    #[test]
+    fn legacy_spl_token_mint_also_uses_transfer_checked() {
+        // A plain `spl_token` mint can never carry a `TransferFeeConfig`
+        // extension, but it still accepts `TransferChecked` — so routing
+        // every vault payout through the checked instruction, regardless of
+        // which token program owns the mint, is safe for both programs.
+        let token_program = spl_token::id();
+        let mint = Pubkey::new_unique();
+        let source = Pubkey::new_unique();
+        let destination = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+
+        let instruction =
+            build_transfer_instruction(&token_program, &mint, &source, &destination, &authority, 500, 2, 0).unwrap();
+
+        assert_eq!(instruction.program_id, spl_token::id());
+    }
+}
@@ -0,0 +1,371 @@
+//! A fluent builder for standing up a fully initialized market against a
+//! live RPC endpoint, so downstream integrators don't each have to
+//! reimplement the account-creation boilerplate that used to live inline
+//! in the integration test. Modeled on the `SerumCookie`/`ListingKeys`
+//! harnesses that downstream programs already use to compose with
+//! serum-dex in their own tests.
+
+use std::num::NonZeroU64;
+
+use solana_client::rpc_client::RpcClient;
+use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
+use solana_sdk::program_pack::Pack;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::system_instruction;
+use solana_sdk::transaction::Transaction;
+use spl_token::state::{Account, Mint};
+
+use crate::instruction::{init_open_orders, initialize_market, new_order};
+use crate::matching::{OrderType, Side};
+use crate::state::gen_vault_signer_key;
+
+const MARKET_LEN: u64 = 388;
+const BIDS_LEN: u64 = 8_388_620;
+const ASKS_LEN: u64 = 8_388_620;
+const REQ_Q_LEN: u64 = 652;
+const EVENT_Q_LEN: u64 = 65_548;
+const OPEN_ORDERS_LEN: u64 = 3_228;
+
+/// Fluent builder for a brand-new market. Defaults: 6 decimals and 1-unit
+/// lots on both sides, no dust threshold, no fee-rate discount, and empty
+/// vaults.
+pub struct MarketBuilder {
+    program_id: Pubkey,
+    coin_decimals: u8,
+    pc_decimals: u8,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    pc_dust_threshold: u64,
+    fee_rate_bps: u16,
+    initial_coin_vault_balance: u64,
+    initial_pc_vault_balance: u64,
+}
+
+impl MarketBuilder {
+    pub fn new(program_id: Pubkey) -> Self {
+        MarketBuilder {
+            program_id,
+            coin_decimals: 6,
+            pc_decimals: 6,
+            coin_lot_size: 1,
+            pc_lot_size: 1,
+            pc_dust_threshold: 0,
+            fee_rate_bps: 0,
+            initial_coin_vault_balance: 0,
+            initial_pc_vault_balance: 0,
+        }
+    }
+
+    pub fn coin_decimals(mut self, decimals: u8) -> Self {
+        self.coin_decimals = decimals;
+        self
+    }
+
+    pub fn pc_decimals(mut self, decimals: u8) -> Self {
+        self.pc_decimals = decimals;
+        self
+    }
+
+    pub fn coin_lot_size(mut self, lot_size: u64) -> Self {
+        self.coin_lot_size = lot_size;
+        self
+    }
+
+    pub fn pc_lot_size(mut self, lot_size: u64) -> Self {
+        self.pc_lot_size = lot_size;
+        self
+    }
+
+    pub fn pc_dust_threshold(mut self, threshold: u64) -> Self {
+        self.pc_dust_threshold = threshold;
+        self
+    }
+
+    pub fn fee_rate_bps(mut self, bps: u16) -> Self {
+        self.fee_rate_bps = bps;
+        self
+    }
+
+    pub fn initial_vault_balances(mut self, coin: u64, pc: u64) -> Self {
+        self.initial_coin_vault_balance = coin;
+        self.initial_pc_vault_balance = pc;
+        self
+    }
+
+    /// Creates and funds every account a market needs, then sends
+    /// `InitializeMarket`. Returns the keypairs/pubkeys the caller needs to
+    /// drive further instructions against the market.
+    pub fn build(self, rpc_client: &RpcClient, payer: &Keypair) -> anyhow::Result<MarketKeys> {
+        self.create_accounts(rpc_client, payer)?.initialize(rpc_client, payer)
+    }
+
+    /// Creates and funds every account a market needs, stopping short of
+    /// sending `InitializeMarket` -- split out of `build` so a caller that
+    /// needs to send or measure that instruction itself (e.g. `bench.rs`'s
+    /// compute-unit profiling) can still reuse this bootstrap boilerplate.
+    pub fn create_accounts(self, rpc_client: &RpcClient, payer: &Keypair) -> anyhow::Result<UninitializedMarket> {
+        let market = Keypair::new();
+        let bids = Keypair::new();
+        let asks = Keypair::new();
+        let req_q = Keypair::new();
+        let event_q = Keypair::new();
+        let coin_mint = Keypair::new();
+        let pc_mint = Keypair::new();
+        let coin_vault = Keypair::new();
+        let pc_vault = Keypair::new();
+
+        let mut vault_signer_nonce = 0u64;
+        let vault_signer = loop {
+            assert!(vault_signer_nonce < 100, "failed to find a valid vault signer nonce");
+            if let Ok(pk) = gen_vault_signer_key(vault_signer_nonce, &market.pubkey(), &self.program_id) {
+                break pk;
+            }
+            vault_signer_nonce += 1;
+        };
+
+        create_account(rpc_client, payer, &market, MARKET_LEN, &self.program_id)?;
+        create_account(rpc_client, payer, &bids, BIDS_LEN, &self.program_id)?;
+        create_account(rpc_client, payer, &asks, ASKS_LEN, &self.program_id)?;
+        create_account(rpc_client, payer, &req_q, REQ_Q_LEN, &self.program_id)?;
+        create_account(rpc_client, payer, &event_q, EVENT_Q_LEN, &self.program_id)?;
+
+        create_account(rpc_client, payer, &coin_mint, Mint::get_packed_len() as u64, &spl_token::id())?;
+        create_account(rpc_client, payer, &pc_mint, Mint::get_packed_len() as u64, &spl_token::id())?;
+        send(
+            rpc_client,
+            payer,
+            &[payer, &coin_mint, &pc_mint],
+            &[
+                spl_token::instruction::initialize_mint(&spl_token::id(), &coin_mint.pubkey(), &payer.pubkey(), None, self.coin_decimals)?,
+                spl_token::instruction::initialize_mint(&spl_token::id(), &pc_mint.pubkey(), &payer.pubkey(), None, self.pc_decimals)?,
+            ],
+        )?;
+
+        create_account(rpc_client, payer, &coin_vault, Account::get_packed_len() as u64, &spl_token::id())?;
+        create_account(rpc_client, payer, &pc_vault, Account::get_packed_len() as u64, &spl_token::id())?;
+        send(
+            rpc_client,
+            payer,
+            &[payer],
+            &[
+                spl_token::instruction::initialize_account(&spl_token::id(), &coin_vault.pubkey(), &coin_mint.pubkey(), &vault_signer)?,
+                spl_token::instruction::initialize_account(&spl_token::id(), &pc_vault.pubkey(), &pc_mint.pubkey(), &vault_signer)?,
+                spl_token::instruction::mint_to(&spl_token::id(), &coin_mint.pubkey(), &coin_vault.pubkey(), &payer.pubkey(), &[], self.initial_coin_vault_balance)?,
+                spl_token::instruction::mint_to(&spl_token::id(), &pc_mint.pubkey(), &pc_vault.pubkey(), &payer.pubkey(), &[], self.initial_pc_vault_balance)?,
+            ],
+        )?;
+
+        Ok(UninitializedMarket {
+            keys: MarketKeys {
+                program_id: self.program_id,
+                market,
+                bids,
+                asks,
+                req_q,
+                event_q,
+                coin_mint,
+                pc_mint,
+                coin_vault,
+                pc_vault,
+                vault_signer,
+                vault_signer_nonce,
+            },
+            coin_lot_size: self.coin_lot_size,
+            pc_lot_size: self.pc_lot_size,
+            pc_dust_threshold: self.pc_dust_threshold,
+            fee_rate_bps: self.fee_rate_bps,
+        })
+    }
+}
+
+/// A market whose accounts have all been created and funded, but that
+/// hasn't yet had `InitializeMarket` sent. Returned by
+/// `MarketBuilder::create_accounts` for callers that need to send or
+/// measure that instruction themselves.
+pub struct UninitializedMarket {
+    pub keys: MarketKeys,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    pc_dust_threshold: u64,
+    fee_rate_bps: u16,
+}
+
+impl UninitializedMarket {
+    /// Builds (but does not send) this market's `InitializeMarket`
+    /// instruction.
+    pub fn initialize_market_instruction(&self) -> Result<Instruction, ProgramError> {
+        initialize_market(
+            &self.keys.market.pubkey(),
+            &self.keys.program_id,
+            &self.keys.coin_mint.pubkey(),
+            &self.keys.pc_mint.pubkey(),
+            &self.keys.coin_vault.pubkey(),
+            &self.keys.pc_vault.pubkey(),
+            None,
+            None,
+            None,
+            &self.keys.bids.pubkey(),
+            &self.keys.asks.pubkey(),
+            &self.keys.req_q.pubkey(),
+            &self.keys.event_q.pubkey(),
+            self.coin_lot_size,
+            self.pc_lot_size,
+            self.keys.vault_signer_nonce,
+            self.pc_dust_threshold,
+            self.fee_rate_bps,
+        )
+    }
+
+    /// Sends `InitializeMarket` and returns the now-initialized market's
+    /// keys.
+    pub fn initialize(self, rpc_client: &RpcClient, payer: &Keypair) -> anyhow::Result<MarketKeys> {
+        send(rpc_client, payer, &[payer], &[self.initialize_market_instruction()?])?;
+        Ok(self.keys)
+    }
+}
+
+/// Keypairs/pubkeys for a market that `MarketBuilder::build` just
+/// initialized, plus helpers for the per-user setup every test needs on
+/// top of it.
+pub struct MarketKeys {
+    pub program_id: Pubkey,
+    pub market: Keypair,
+    pub bids: Keypair,
+    pub asks: Keypair,
+    pub req_q: Keypair,
+    pub event_q: Keypair,
+    pub coin_mint: Keypair,
+    pub pc_mint: Keypair,
+    pub coin_vault: Keypair,
+    pub pc_vault: Keypair,
+    pub vault_signer: Pubkey,
+    pub vault_signer_nonce: u64,
+}
+
+impl MarketKeys {
+    /// Creates and initializes an `OpenOrders` account owned by `owner`.
+    pub fn open_orders(&self, rpc_client: &RpcClient, payer: &Keypair, owner: &Keypair) -> anyhow::Result<Keypair> {
+        let open_orders = Keypair::new();
+        create_account(rpc_client, payer, &open_orders, OPEN_ORDERS_LEN, &self.program_id)?;
+        send(
+            rpc_client,
+            payer,
+            &[payer, owner],
+            &[init_open_orders(
+                &self.program_id,
+                &open_orders.pubkey(),
+                &owner.pubkey(),
+                &self.market.pubkey(),
+                None,
+            )?],
+        )?;
+        Ok(open_orders)
+    }
+
+    /// Creates and funds a coin/pc wallet pair for `owner`, useful for
+    /// seeding a maker or taker before submitting orders.
+    pub fn wallets(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &Keypair,
+        owner: &Pubkey,
+        coin_amount: u64,
+        pc_amount: u64,
+    ) -> anyhow::Result<(Keypair, Keypair)> {
+        let coin_wallet = Keypair::new();
+        let pc_wallet = Keypair::new();
+        create_account(rpc_client, payer, &coin_wallet, Account::get_packed_len() as u64, &spl_token::id())?;
+        create_account(rpc_client, payer, &pc_wallet, Account::get_packed_len() as u64, &spl_token::id())?;
+        send(
+            rpc_client,
+            payer,
+            &[payer],
+            &[
+                spl_token::instruction::initialize_account(&spl_token::id(), &coin_wallet.pubkey(), &self.coin_mint.pubkey(), owner)?,
+                spl_token::instruction::initialize_account(&spl_token::id(), &pc_wallet.pubkey(), &self.pc_mint.pubkey(), owner)?,
+                spl_token::instruction::mint_to(&spl_token::id(), &self.coin_mint.pubkey(), &coin_wallet.pubkey(), &payer.pubkey(), &[], coin_amount)?,
+                spl_token::instruction::mint_to(&spl_token::id(), &self.pc_mint.pubkey(), &pc_wallet.pubkey(), &payer.pubkey(), &[], pc_amount)?,
+            ],
+        )?;
+        Ok((coin_wallet, pc_wallet))
+    }
+
+    /// Convenience wrapper around `new_order` for a resting limit order
+    /// placed by `open_orders`'s owner.
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_limit_order(
+        &self,
+        rpc_client: &RpcClient,
+        payer: &Keypair,
+        open_orders: &Pubkey,
+        order_payer: &Pubkey,
+        owner: &Pubkey,
+        side: Side,
+        limit_price: NonZeroU64,
+        max_coin_qty: NonZeroU64,
+    ) -> anyhow::Result<()> {
+        send(
+            rpc_client,
+            payer,
+            &[payer],
+            &[new_order(
+                &self.market.pubkey(),
+                open_orders,
+                &self.req_q.pubkey(),
+                &self.event_q.pubkey(),
+                &self.bids.pubkey(),
+                &self.asks.pubkey(),
+                order_payer,
+                owner,
+                &self.coin_vault.pubkey(),
+                &self.pc_vault.pubkey(),
+                spl_token::id(),
+                solana_sdk::sysvar::rent::id(),
+                None,
+                &self.program_id,
+                side,
+                limit_price,
+                max_coin_qty,
+                OrderType::Limit,
+            )?],
+        )
+    }
+}
+
+fn create_account(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    account: &Keypair,
+    len: u64,
+    owner: &Pubkey,
+) -> anyhow::Result<()> {
+    let blockhash = rpc_client.get_latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(
+        &[system_instruction::create_account(
+            &payer.pubkey(),
+            &account.pubkey(),
+            rpc_client.get_minimum_balance_for_rent_exemption(len as usize)?,
+            len,
+            owner,
+        )],
+        Some(&payer.pubkey()),
+        &[payer, account],
+        blockhash,
+    );
+    rpc_client.send_and_confirm_transaction(&tx)?;
+    Ok(())
+}
+
+fn send(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    signers: &[&Keypair],
+    instructions: &[solana_sdk::instruction::Instruction],
+) -> anyhow::Result<()> {
+    let blockhash = rpc_client.get_latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), signers, blockhash);
+    rpc_client.send_and_confirm_transaction(&tx)?;
+    Ok(())
+}
@@ -0,0 +1,286 @@
+//! Compute-unit profiling harness for every dex instruction.
+//!
+//! Spins up a local `TestValidatorGenesis`, bootstraps a market under
+//! parameterized load (`BENCH_RESTING_ORDERS` resting asks, `BENCH_QUEUED_EVENTS`
+//! taker fills queued by a threaded multi-user order generator), and records
+//! the consumed compute units for every instruction by reading them back off
+//! transaction metadata. Replaces the `println!`-based profiling that used
+//! to live in `test_serum_dex`.
+//!
+//! This stands up a validator and is too slow to run on every `cargo test`,
+//! so it's marked `#[ignore]`. Run it explicitly:
+//!
+//!     cargo test --test bench -- --ignored --nocapture
+//!
+//! A JSON report is written to `bench_report.json` in the working directory,
+//! one row per instruction per load point, so CI can diff it against a
+//! stored baseline to catch matching-loop cost regressions.
+
+use std::fs::File;
+use std::num::NonZeroU64;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::Serialize;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::Transaction;
+use solana_validator::test_validator::TestValidatorGenesis;
+
+use serum_dex::instruction::{cancel_order, consume_events, match_orders, new_order, settle_funds};
+use serum_dex::matching::{OrderType, Side};
+use serum_dex::testing::MarketBuilder;
+
+/// Default load used when the environment variables below aren't set.
+const DEFAULT_RESTING_ORDERS: usize = 32;
+const DEFAULT_QUEUED_EVENTS: usize = 16;
+
+#[derive(Debug, Serialize)]
+struct CuSample {
+    instruction: String,
+    resting_orders: usize,
+    queued_events: usize,
+    consumed_units: u64,
+}
+
+/// Sends `instructions` as a single transaction and returns the compute
+/// units it consumed, as reported in the confirmed transaction's metadata.
+fn send_and_measure(
+    rpc_client: &solana_client::rpc_client::RpcClient,
+    payer: &Keypair,
+    signers: &[&Keypair],
+    instructions: &[Instruction],
+) -> anyhow::Result<u64> {
+    let blockhash = rpc_client.get_latest_blockhash()?;
+    let tx = Transaction::new_signed_with_payer(instructions, Some(&payer.pubkey()), signers, blockhash);
+    let signature = rpc_client.send_and_confirm_transaction(&tx)?;
+    let meta = rpc_client
+        .get_transaction(&signature, solana_transaction_status::UiTransactionEncoding::Json)?
+        .transaction
+        .meta
+        .ok_or_else(|| anyhow::anyhow!("confirmed transaction is missing metadata"))?;
+    Ok(meta.compute_units_consumed.unwrap_or(0))
+}
+
+#[test]
+#[ignore]
+fn bench_compute_units() -> anyhow::Result<()> {
+    let resting_orders = std::env::var("BENCH_RESTING_ORDERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RESTING_ORDERS);
+    let queued_events = std::env::var("BENCH_QUEUED_EVENTS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_QUEUED_EVENTS);
+
+    let program_id = Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin")?;
+    let (test_validator, payer) = TestValidatorGenesis::default()
+        .add_program("serum_dex", program_id)
+        .start();
+    let rpc_client = test_validator.get_rpc_client();
+    let samples = Arc::new(Mutex::new(Vec::<CuSample>::new()));
+
+    // `create_accounts` stops short of sending `InitializeMarket`, so that
+    // instruction's own compute cost can still be measured below rather
+    // than being hidden inside `MarketBuilder::build`.
+    let uninitialized = MarketBuilder::new(program_id)
+        .pc_dust_threshold(5)
+        .create_accounts(&rpc_client, &payer)?;
+    let cu = send_and_measure(
+        &rpc_client,
+        &payer,
+        &[&payer],
+        &[uninitialized.initialize_market_instruction()?],
+    )?;
+    samples.lock().unwrap().push(CuSample {
+        instruction: "initialize_market".into(),
+        resting_orders,
+        queued_events,
+        consumed_units: cu,
+    });
+    let m = Arc::new(uninitialized.keys);
+
+    let maker_oo = m.open_orders(&rpc_client, &payer, &payer)?;
+    let (maker_coin, maker_pc) = m.wallets(&rpc_client, &payer, &payer.pubkey(), 1_000_000, 1_000_000)?;
+
+    // Lay down the resting book: `resting_orders` maker asks at increasing
+    // prices, so a taker sweep has to walk several price levels.
+    for i in 0..resting_orders {
+        let price = NonZeroU64::new(10 + i as u64).unwrap();
+        let qty = NonZeroU64::new(10).unwrap();
+        let cu = send_and_measure(
+            &rpc_client,
+            &payer,
+            &[&payer],
+            &[new_order(
+                &m.market.pubkey(),
+                &maker_oo.pubkey(),
+                &m.req_q.pubkey(),
+                &m.event_q.pubkey(),
+                &m.bids.pubkey(),
+                &m.asks.pubkey(),
+                &maker_coin.pubkey(),
+                &payer.pubkey(),
+                &m.coin_vault.pubkey(),
+                &m.pc_vault.pubkey(),
+                spl_token::id(),
+                solana_sdk::sysvar::rent::id(),
+                None,
+                &program_id,
+                Side::Ask,
+                price,
+                qty,
+                OrderType::Limit,
+            )?],
+        )?;
+        if i == 0 {
+            samples.lock().unwrap().push(CuSample {
+                instruction: "new_order (resting maker)".into(),
+                resting_orders,
+                queued_events,
+                consumed_units: cu,
+            });
+        }
+    }
+
+    // Multi-user order generator: `queued_events` takers concurrently send
+    // IOC bids, queuing up fill events for `consume_events` to drain.
+    let handles: Vec<_> = (0..queued_events)
+        .map(|_| {
+            let rpc_client = test_validator.get_rpc_client();
+            let payer = Keypair::from_bytes(&payer.to_bytes()).unwrap();
+            let m = Arc::clone(&m);
+            thread::spawn(move || -> anyhow::Result<u64> {
+                let open_orders = m.open_orders(&rpc_client, &payer, &payer)?;
+                let (_, pc_wallet) = m.wallets(&rpc_client, &payer, &payer.pubkey(), 0, 10_000)?;
+
+                send_and_measure(
+                    &rpc_client,
+                    &payer,
+                    &[&payer],
+                    &[new_order(
+                        &m.market.pubkey(),
+                        &open_orders.pubkey(),
+                        &m.req_q.pubkey(),
+                        &m.event_q.pubkey(),
+                        &m.bids.pubkey(),
+                        &m.asks.pubkey(),
+                        &pc_wallet.pubkey(),
+                        &payer.pubkey(),
+                        &m.coin_vault.pubkey(),
+                        &m.pc_vault.pubkey(),
+                        spl_token::id(),
+                        solana_sdk::sysvar::rent::id(),
+                        None,
+                        &m.program_id,
+                        Side::Bid,
+                        NonZeroU64::new(10 + resting_orders as u64).unwrap(),
+                        NonZeroU64::new(5).unwrap(),
+                        OrderType::ImmediateOrCancel,
+                    )?],
+                )
+            })
+        })
+        .collect();
+
+    let mut taker_cus = Vec::new();
+    for handle in handles {
+        if let Ok(cu) = handle.join().unwrap() {
+            taker_cus.push(cu);
+        }
+    }
+    if let Some(cu) = taker_cus.first() {
+        samples.lock().unwrap().push(CuSample {
+            instruction: "new_order (taker, concurrent)".into(),
+            resting_orders,
+            queued_events,
+            consumed_units: *cu,
+        });
+    }
+
+    let cu = send_and_measure(
+        &rpc_client,
+        &payer,
+        &[&payer],
+        &[match_orders(&program_id, &m.market.pubkey(), &m.req_q.pubkey(), &m.event_q.pubkey(), &m.bids.pubkey(), &m.asks.pubkey(), 65535)?],
+    )?;
+    samples.lock().unwrap().push(CuSample {
+        instruction: "match_orders".into(),
+        resting_orders,
+        queued_events,
+        consumed_units: cu,
+    });
+
+    let cu = send_and_measure(
+        &rpc_client,
+        &payer,
+        &[&payer],
+        &[consume_events(&program_id, &m.market.pubkey(), &[maker_oo.pubkey()], &m.event_q.pubkey(), 65535)?],
+    )?;
+    samples.lock().unwrap().push(CuSample {
+        instruction: "consume_events".into(),
+        resting_orders,
+        queued_events,
+        consumed_units: cu,
+    });
+
+    let cu = send_and_measure(
+        &rpc_client,
+        &payer,
+        &[&payer],
+        &[settle_funds(
+            &program_id,
+            &m.market.pubkey(),
+            &maker_oo.pubkey(),
+            &payer.pubkey(),
+            &m.coin_vault.pubkey(),
+            &maker_coin.pubkey(),
+            &m.pc_vault.pubkey(),
+            &maker_pc.pubkey(),
+            &m.vault_signer,
+            &spl_token::id(),
+        )?],
+    )?;
+    samples.lock().unwrap().push(CuSample {
+        instruction: "settle_funds".into(),
+        resting_orders,
+        queued_events,
+        consumed_units: cu,
+    });
+
+    let cu = send_and_measure(
+        &rpc_client,
+        &payer,
+        &[&payer],
+        &[cancel_order(
+            &program_id,
+            &m.market.pubkey(),
+            &m.bids.pubkey(),
+            &m.asks.pubkey(),
+            &maker_oo.pubkey(),
+            &payer.pubkey(),
+            Side::Ask,
+            0,
+        )?],
+    )?;
+    samples.lock().unwrap().push(CuSample {
+        instruction: "cancel_order".into(),
+        resting_orders,
+        queued_events,
+        consumed_units: cu,
+    });
+
+    let samples = samples.lock().unwrap();
+    for sample in samples.iter() {
+        println!(
+            "{:<28} resting={:<5} events={:<5} cu={}",
+            sample.instruction, sample.resting_orders, sample.queued_events, sample.consumed_units
+        );
+    }
+    serde_json::to_writer_pretty(File::create("bench_report.json")?, &*samples)?;
+
+    Ok(())
+}